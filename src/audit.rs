@@ -0,0 +1,66 @@
+//! Per-connection audit logging to its own file under `--log-dir`.
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::Utc;
+use log::warn;
+
+/// A lazily-opened append log for one connection. The file (and its parent
+/// directories) aren't created until the first `record` call, so connections
+/// that never send any data don't leave behind empty log files.
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<Option<std::fs::File>>,
+}
+
+impl AuditLog {
+    /// Builds the log path for a new connection under `log_dir`:
+    /// `YYYY-MM-DD/HH:MM:SS-addr.txt`.
+    pub fn new(log_dir: &Path, peer_addr: Option<SocketAddr>) -> Self {
+        let now = Utc::now();
+        let peer = peer_addr
+            .map(|addr| addr.to_string().replace([':', '.'], "-"))
+            .unwrap_or_else(|| "unknown".to_string());
+        let path = log_dir
+            .join(now.format("%Y-%m-%d").to_string())
+            .join(format!("{}-{}.txt", now.format("%H:%M:%S"), peer));
+
+        Self {
+            path,
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Appends a timestamped `line` to the log, logging (not propagating) any
+    /// I/O error so a connection never fails just because its audit log did.
+    pub fn record(&self, line: &str) {
+        if let Err(err) = self.try_record(line) {
+            warn!(
+                "audit: failed to write to {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+
+    fn try_record(&self, line: &str) -> std::io::Result<()> {
+        let mut guard = self.file.lock().expect("audit log lock poisoned");
+        if guard.is_none() {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            *guard = Some(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        let file = guard.as_mut().expect("just opened above");
+        writeln!(file, "[{}] {}", Utc::now().format("%H:%M:%S%.3f"), line)?;
+        file.flush()
+    }
+}
@@ -0,0 +1,174 @@
+//! `authorized_keys` loading and lookup for public-key authentication.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use anyhow::{Context, Result};
+use log::warn;
+use russh_keys::key;
+use russh_keys::parse_public_key_base64;
+
+/// The set of key fingerprints allowed to authenticate, loaded from an
+/// OpenSSH-style `authorized_keys` file and reloadable in place (e.g. on
+/// SIGHUP) so key rotation doesn't require restarting the server.
+pub struct AuthorizedKeys {
+    path: PathBuf,
+    fingerprints: RwLock<HashSet<String>>,
+}
+
+impl AuthorizedKeys {
+    /// Loads `path`, parsing every `authorized_keys` line it can and warning
+    /// about the ones it can't. A missing file is treated as an empty key
+    /// set rather than an error, since that's a valid (if maximally strict)
+    /// server configuration.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let fingerprints = Self::parse_file(&path)?;
+        Ok(Self {
+            path,
+            fingerprints: RwLock::new(fingerprints),
+        })
+    }
+
+    /// Returns whether `key`'s fingerprint appears in the currently loaded set.
+    pub fn is_authorized(&self, key: &key::PublicKey) -> bool {
+        self.fingerprints
+            .read()
+            .expect("authorized_keys lock poisoned")
+            .contains(&key.fingerprint())
+    }
+
+    /// Re-reads the backing file, replacing the in-memory set on success and
+    /// leaving the previous set in place (with a logged warning) on failure.
+    pub fn reload(&self) {
+        match Self::parse_file(&self.path) {
+            Ok(fresh) => {
+                *self.fingerprints.write().expect("authorized_keys lock poisoned") = fresh;
+                log::info!(
+                    "authorized_keys: reloaded {} key(s) from {}",
+                    self.fingerprints.read().expect("authorized_keys lock poisoned").len(),
+                    self.path.display()
+                );
+            }
+            Err(err) => warn!(
+                "authorized_keys: failed to reload {}: {:#}",
+                self.path.display(),
+                err
+            ),
+        }
+    }
+
+    fn parse_file(path: &Path) -> Result<HashSet<String>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                warn!(
+                    "authorized_keys: {} does not exist; no public keys will be accepted",
+                    path.display()
+                );
+                return Ok(HashSet::new());
+            }
+            Err(err) => {
+                return Err(err).context(format!("reading authorized_keys at {}", path.display()))
+            }
+        };
+
+        let mut fingerprints = HashSet::new();
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // Lines look like `[options] key-type base64-blob [comment]`. We
+            // don't interpret options or comments, just pick out the field
+            // that actually decodes as a key.
+            let Some(key_field) = line
+                .split_whitespace()
+                .find(|field| parse_public_key_base64(field).is_ok())
+            else {
+                warn!(
+                    "authorized_keys:{}: no parseable public key on this line, skipping",
+                    lineno + 1
+                );
+                continue;
+            };
+
+            let key = parse_public_key_base64(key_field)
+                .context("re-parsing a field already confirmed to parse")?;
+            fingerprints.insert(key.fingerprint());
+        }
+
+        Ok(fingerprints)
+    }
+}
+
+/// Default location the server looks for an `authorized_keys` file when
+/// `--authorized-keys` isn't given: `~/.ssh/authorized_keys`.
+pub fn default_authorized_keys_path() -> PathBuf {
+    let home = std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    home.join(".ssh").join("authorized_keys")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KNOWN_KEY_FIELD: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIAEMZBfkuGT5KIQOoqJdRdbzb7mE17A2Nt+w68JDOOH6";
+    const UNKNOWN_KEY_FIELD: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIGDyy7jAjfTQRnF7j43iTyo/zmsecOSiYVLmPXAosgSd";
+    const VALID_KEY_LINE: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAEMZBfkuGT5KIQOoqJdRdbzb7mE17A2Nt+w68JDOOH6 test@reverb";
+
+    fn write_temp(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("reverb-test-{}-{}", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn parse_file_accepts_valid_lines_and_skips_bad_ones() {
+        let path = write_temp(
+            "authorized-keys-valid",
+            &format!("# a comment\n{}\nnot a key at all\n\n", VALID_KEY_LINE),
+        );
+        let fingerprints = AuthorizedKeys::parse_file(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(fingerprints.len(), 1);
+    }
+
+    #[test]
+    fn parse_file_treats_missing_file_as_empty_set() {
+        let path = std::env::temp_dir().join("reverb-test-authorized-keys-does-not-exist");
+        let _ = std::fs::remove_file(&path);
+        let fingerprints = AuthorizedKeys::parse_file(&path).unwrap();
+        assert!(fingerprints.is_empty());
+    }
+
+    #[test]
+    fn is_authorized_accepts_loaded_keys_and_rejects_others() {
+        let path = write_temp("authorized-keys-is-authorized", &format!("{}\n", VALID_KEY_LINE));
+        let keys = AuthorizedKeys::load(path.clone()).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let known = parse_public_key_base64(KNOWN_KEY_FIELD).unwrap();
+        assert!(keys.is_authorized(&known));
+
+        let unknown = parse_public_key_base64(UNKNOWN_KEY_FIELD).unwrap();
+        assert!(!keys.is_authorized(&unknown));
+    }
+
+    #[test]
+    fn reload_picks_up_newly_added_keys() {
+        let path = write_temp("authorized-keys-reload", "");
+        let keys = AuthorizedKeys::load(path.clone()).unwrap();
+        let known = parse_public_key_base64(KNOWN_KEY_FIELD).unwrap();
+        assert!(!keys.is_authorized(&known));
+
+        std::fs::write(&path, format!("{}\n", VALID_KEY_LINE)).unwrap();
+        keys.reload();
+        std::fs::remove_file(&path).ok();
+        assert!(keys.is_authorized(&known));
+    }
+}
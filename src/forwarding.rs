@@ -0,0 +1,110 @@
+//! TCP port forwarding helpers shared by the `-L`/`-R` flags.
+
+use anyhow::{Context, Result};
+use russh::{client, Channel, ChannelMsg};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// One endpoint of a `-L`/`-R` forward spec: `host:port`.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+}
+
+/// Parses a `[bind_host:]bind_port:host:port` forward spec (the format
+/// accepted by both `-L` and `-R`) into a `(bind, target)` endpoint pair.
+pub fn parse_forward_spec(spec: &str) -> Result<(Endpoint, Endpoint)> {
+    let parts: Vec<&str> = spec.split(':').collect();
+    let (bind, target) = match parts.as_slice() {
+        [bind_port, target_host, target_port] => (
+            Endpoint {
+                host: "127.0.0.1".to_string(),
+                port: bind_port.parse().context("invalid bind port")?,
+            },
+            Endpoint {
+                host: target_host.to_string(),
+                port: target_port.parse().context("invalid target port")?,
+            },
+        ),
+        [bind_host, bind_port, target_host, target_port] => (
+            Endpoint {
+                host: bind_host.to_string(),
+                port: bind_port.parse().context("invalid bind port")?,
+            },
+            Endpoint {
+                host: target_host.to_string(),
+                port: target_port.parse().context("invalid target port")?,
+            },
+        ),
+        _ => anyhow::bail!(
+            "invalid forward spec {:?}, expected [bind_host:]bind_port:host:port",
+            spec
+        ),
+    };
+    Ok((bind, target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn three_part_spec_binds_on_loopback() {
+        let (bind, target) = parse_forward_spec("8080:example.com:80").unwrap();
+        assert_eq!(bind.host, "127.0.0.1");
+        assert_eq!(bind.port, 8080);
+        assert_eq!(target.host, "example.com");
+        assert_eq!(target.port, 80);
+    }
+
+    #[test]
+    fn four_part_spec_uses_given_bind_host() {
+        let (bind, target) = parse_forward_spec("0.0.0.0:2222:localhost:22").unwrap();
+        assert_eq!(bind.host, "0.0.0.0");
+        assert_eq!(bind.port, 2222);
+        assert_eq!(target.host, "localhost");
+        assert_eq!(target.port, 22);
+    }
+
+    #[test]
+    fn bind_port_zero_parses_as_zero() {
+        let (bind, _target) = parse_forward_spec("0:localhost:22").unwrap();
+        assert_eq!(bind.port, 0);
+    }
+
+    #[test]
+    fn rejects_specs_with_wrong_number_of_parts() {
+        assert!(parse_forward_spec("not-enough-parts").is_err());
+        assert!(parse_forward_spec("a:b:c:d:e").is_err());
+    }
+}
+
+/// Copies bytes in both directions between `stream` and `channel` until
+/// either side closes, logging nothing itself — callers decide how to report
+/// a failed forward. Used on the client side, where an opened or accepted
+/// channel is a `Channel` object the caller owns outright (unlike the
+/// server, where inbound channel data is dispatched through `Handler::data`).
+pub async fn bridge_client_channel(stream: TcpStream, mut channel: Channel<client::Msg>) -> Result<()> {
+    let (mut tcp_read, mut tcp_write) = stream.into_split();
+    let mut buf = [0u8; 4096];
+    loop {
+        tokio::select! {
+            result = tcp_read.read(&mut buf) => {
+                let n = result?;
+                if n == 0 {
+                    channel.eof().await?;
+                    break;
+                }
+                channel.data(&buf[..n]).await?;
+            }
+            msg = channel.wait() => {
+                match msg {
+                    Some(ChannelMsg::Data { data }) => tcp_write.write_all(&data).await?,
+                    Some(_) | None => break,
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! Key pair generation for `--generate-key` and the ephemeral host key fallback.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use log::info;
+use russh_keys::key;
+
+/// Which kind of key pair to generate. Ed25519 is the default: it's fast to
+/// generate and is what `ssh-keygen` itself defaults to these days.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum KeyKind {
+    #[default]
+    Ed25519,
+    Rsa,
+}
+
+/// Generates a fresh key pair of the requested kind.
+pub fn generate(kind: KeyKind) -> Result<key::KeyPair> {
+    match kind {
+        KeyKind::Ed25519 => {
+            key::KeyPair::generate_ed25519().context("generating an Ed25519 key pair")
+        }
+        KeyKind::Rsa => key::KeyPair::generate_rsa(3072, key::SignatureHash::SHA2_512)
+            .context("generating an RSA key pair"),
+    }
+}
+
+/// Generates an ephemeral host key for a server started without `--key`, so
+/// it can come up with zero setup. The key lives only for this process's
+/// lifetime and is logged as such, since clients will see a new fingerprint
+/// on every restart.
+pub fn ephemeral_host_key() -> Result<key::KeyPair> {
+    let pair = generate(KeyKind::Ed25519)?;
+    log::warn!(
+        "Server: no --key given, generated an ephemeral host key for this run only \
+         (clients will see a new fingerprint next time)"
+    );
+    Ok(pair)
+}
+
+/// Writes `pair`'s private half to `path` as a PKCS8 PEM file (permissions
+/// `0600` on Unix) and its public half to `path.pub` in `authorized_keys`
+/// format, encrypting the private key with `passphrase` when given.
+///
+/// KNOWN LIMITATION: this writes PKCS8, not OpenSSH's native
+/// `openssh-key-v1` format, so the private key won't round-trip through
+/// `ssh-keygen`/real OpenSSH tooling — only through
+/// `russh_keys::load_secret_key`, which is what this binary's own `--key`
+/// flag uses to read it back. Needs sign-off before this subcommand is
+/// advertised as producing keys usable outside reverb itself; see the
+/// warning this function logs after writing the key.
+pub fn write_key_pair(pair: &key::KeyPair, path: &Path, passphrase: Option<&str>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("creating {}", parent.display()))?;
+    }
+
+    let mut private_pem = Vec::new();
+    match passphrase {
+        Some(passphrase) => key::PKCS8_ENCRYPTED
+            .write_secret_key(pair, &mut private_pem, passphrase)
+            .context("encoding encrypted private key")?,
+        None => key::PKCS8
+            .write_secret_key(pair, &mut private_pem)
+            .context("encoding private key")?,
+    };
+    std::fs::write(path, &private_pem)
+        .with_context(|| format!("writing private key to {}", path.display()))?;
+    set_private_key_permissions(path)?;
+
+    let public_path = append_extension(path, "pub");
+    let public_line = format!(
+        "{} {} reverb-generated-key\n",
+        pair.clone_public_key()?.name(),
+        pair.clone_public_key()?.public_key_base64()
+    );
+    std::fs::write(&public_path, public_line)
+        .with_context(|| format!("writing public key to {}", public_path.display()))?;
+
+    log::warn!(
+        "keygen: {} is PKCS8, not OpenSSH's openssh-key-v1 format — it will NOT load in \
+         ssh-keygen/sshd or other OpenSSH tooling, only in reverb itself. Known limitation, \
+         pending sign-off; do not advertise this subcommand as a ssh-keygen replacement",
+        path.display()
+    );
+    info!(
+        "keygen: wrote private key to {} and public key to {}",
+        path.display(),
+        public_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_private_key_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("setting permissions on {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn set_private_key_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn append_extension(path: &Path, ext: &str) -> std::path::PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(ext);
+    path.with_file_name(name)
+}
+
+/// Prompts for a passphrase on the terminal, returning `None` if the user
+/// enters an empty line (i.e. "no passphrase").
+pub fn prompt_passphrase() -> Result<Option<String>> {
+    let passphrase = rpassword::prompt_password("Passphrase (empty for none): ")
+        .context("reading passphrase from terminal")?;
+    Ok(if passphrase.is_empty() {
+        None
+    } else {
+        Some(passphrase)
+    })
+}
@@ -0,0 +1,195 @@
+//! Client-side host-key verification against a `known_hosts`-style file.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use russh_keys::key;
+
+/// How strictly the client verifies a server's host key.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum HostKeyPolicy {
+    /// Reject any host not already present in the known_hosts file.
+    Strict,
+    /// Trust-on-first-use: accept and remember a host seen for the first
+    /// time, but reject mismatches against an already-known entry.
+    #[default]
+    AcceptNew,
+    /// Accept every host key without checking or recording it. For testing.
+    NoVerify,
+}
+
+/// A `known_hosts`-style store mapping `host:port` to the fingerprint of the
+/// key last seen for it.
+pub struct KnownHosts {
+    path: PathBuf,
+    policy: HostKeyPolicy,
+}
+
+impl KnownHosts {
+    pub fn new(path: PathBuf, policy: HostKeyPolicy) -> Self {
+        Self { path, policy }
+    }
+
+    /// Verifies `key` against the stored entry for `host:port`, recording a
+    /// new entry if the policy allows it. Returns `Ok(false)` (never an
+    /// error) when the key should be rejected, so callers can surface a
+    /// plain "handshake refused" rather than propagating a reason.
+    pub fn check(&self, host: &str, port: u16, key: &key::PublicKey) -> Result<bool> {
+        if matches!(self.policy, HostKeyPolicy::NoVerify) {
+            return Ok(true);
+        }
+
+        let id = format!("{}:{}", host, port);
+        let fingerprint = key.fingerprint();
+        let entries = Self::load(&self.path)?;
+
+        match entries.get(&id) {
+            Some(stored) if *stored == fingerprint => Ok(true),
+            Some(stored) => {
+                warn!(
+                    "known_hosts: key for {} has changed! expected {}, got {} \
+                     (possible MITM, refusing to connect)",
+                    id, stored, fingerprint
+                );
+                Ok(false)
+            }
+            None if matches!(self.policy, HostKeyPolicy::Strict) => {
+                warn!(
+                    "known_hosts: {} is not a known host and strict checking is enabled",
+                    id
+                );
+                Ok(false)
+            }
+            None => {
+                self.append(&id, &fingerprint)?;
+                info!("known_hosts: trusting {} on first use ({})", id, fingerprint);
+                Ok(true)
+            }
+        }
+    }
+
+    fn load(path: &Path) -> Result<std::collections::HashMap<String, String>> {
+        let mut entries = std::collections::HashMap::new();
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+            Err(err) => {
+                return Err(err).context(format!("reading known_hosts at {}", path.display()))
+            }
+        };
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some((id, fingerprint)) = line.split_once(' ') {
+                entries.insert(id.to_string(), fingerprint.to_string());
+            }
+        }
+        Ok(entries)
+    }
+
+    fn append(&self, id: &str, fingerprint: &str) -> Result<()> {
+        use std::io::Write;
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("creating {}", parent.display()))?;
+        }
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("opening known_hosts at {}", self.path.display()))?;
+        writeln!(file, "{} {}", id, fingerprint)
+            .context("writing known_hosts entry")?;
+        Ok(())
+    }
+}
+
+/// Default location the client looks for its known_hosts file:
+/// `~/.ssh/known_hosts`.
+pub fn default_known_hosts_path() -> PathBuf {
+    let home = std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    home.join(".ssh").join("known_hosts")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh_keys::parse_public_key_base64;
+
+    const KEY_FIELD_A: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIAHFWtvQR6+PuOiUYgbrjwhZO4P2oAJQBYs8OiCsauvY";
+    const KEY_FIELD_B: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIEchbwE4hI6HADXIrcHa02tgU+KaqHNK3IGf3qBD12vH";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("reverb-test-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn load_treats_missing_file_as_empty() {
+        let path = temp_path("known-hosts-missing");
+        let _ = std::fs::remove_file(&path);
+        let entries = KnownHosts::load(&path).unwrap();
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn load_parses_host_and_fingerprint_pairs() {
+        let path = temp_path("known-hosts-entries");
+        std::fs::write(&path, "# comment\nexample.com:22 SHA256:abc123\n\n").unwrap();
+        let entries = KnownHosts::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(entries.get("example.com:22"), Some(&"SHA256:abc123".to_string()));
+    }
+
+    #[test]
+    fn accept_new_trusts_on_first_use_and_remembers() {
+        let path = temp_path("known-hosts-accept-new");
+        let _ = std::fs::remove_file(&path);
+        let hosts = KnownHosts::new(path.clone(), HostKeyPolicy::AcceptNew);
+        let key = parse_public_key_base64(KEY_FIELD_A).unwrap();
+
+        assert!(hosts.check("example.com", 22, &key).unwrap());
+        // A later connection with the same key is still trusted.
+        assert!(hosts.check("example.com", 22, &key).unwrap());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn accept_new_rejects_a_changed_key() {
+        let path = temp_path("known-hosts-mismatch");
+        let _ = std::fs::remove_file(&path);
+        let hosts = KnownHosts::new(path.clone(), HostKeyPolicy::AcceptNew);
+        let first = parse_public_key_base64(KEY_FIELD_A).unwrap();
+        let second = parse_public_key_base64(KEY_FIELD_B).unwrap();
+
+        assert!(hosts.check("example.com", 22, &first).unwrap());
+        assert!(!hosts.check("example.com", 22, &second).unwrap());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn strict_rejects_an_unknown_host() {
+        let path = temp_path("known-hosts-strict");
+        let _ = std::fs::remove_file(&path);
+        let hosts = KnownHosts::new(path, HostKeyPolicy::Strict);
+        let key = parse_public_key_base64(KEY_FIELD_A).unwrap();
+
+        assert!(!hosts.check("example.com", 22, &key).unwrap());
+    }
+
+    #[test]
+    fn no_verify_accepts_anything() {
+        let path = temp_path("known-hosts-noverify-does-not-exist");
+        let hosts = KnownHosts::new(path, HostKeyPolicy::NoVerify);
+        let key = parse_public_key_base64(KEY_FIELD_A).unwrap();
+
+        assert!(hosts.check("example.com", 22, &key).unwrap());
+    }
+}
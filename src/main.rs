@@ -1,16 +1,36 @@
+use std::collections::HashMap;
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
 use clap::Parser;
 use log::info;
-use russh::server::{Auth, Server as _, Session as ServerSession};
+use russh::server::{Auth, Handle, Server as _, Session as ServerSession};
 use russh::*;
 use russh_keys::*;
-use tokio::io::AsyncWriteExt;
-use tokio::net::ToSocketAddrs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::{ChildStdin, Command};
+
+mod audit;
+mod auth;
+mod forwarding;
+mod keygen;
+mod known_hosts;
+mod pty;
+mod recorder;
+
+use audit::AuditLog;
+use auth::AuthorizedKeys;
+use forwarding::Endpoint;
+use keygen::KeyKind;
+use known_hosts::{HostKeyPolicy, KnownHosts};
+use recorder::{Recorder, Stream as RecordStream};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -29,9 +49,61 @@ struct Args {
     #[arg(long, default_value = "username")]
     user: String,
 
-    /// Path to the decrypted key file
+    /// Path to the decrypted key file. If omitted, the server generates an
+    /// ephemeral host key for this run; the client always requires one
+    /// (generate it first with --generate-key).
     #[arg(long, short = 'k')]
-    key: PathBuf,
+    key: Option<PathBuf>,
+
+    /// Generate a key pair at --key (and --key.pub) instead of connecting or
+    /// serving.
+    #[arg(long)]
+    generate_key: bool,
+
+    /// Kind of key pair to generate with --generate-key.
+    #[arg(long, value_enum, default_value_t = KeyKind::Ed25519)]
+    key_type: KeyKind,
+
+    /// Encrypt the generated private key with a passphrase, prompted on the
+    /// terminal. Only used with --generate-key.
+    #[arg(long)]
+    ask_passphrase: bool,
+
+    /// Path to the server's authorized_keys file. Defaults to
+    /// `~/.ssh/authorized_keys`. Reloaded on SIGHUP.
+    #[arg(long)]
+    authorized_keys: Option<PathBuf>,
+
+    /// Path to the client's known_hosts file. Defaults to `~/.ssh/known_hosts`.
+    #[arg(long)]
+    known_hosts: Option<PathBuf>,
+
+    /// How the client verifies the server's host key.
+    #[arg(long, value_enum, default_value_t = HostKeyPolicy::AcceptNew)]
+    host_key_check: HostKeyPolicy,
+
+    /// Record each session as an asciinema cast file in this directory.
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Audit-log each connection's channel data and auth attempts to its own
+    /// file under this directory.
+    #[arg(long)]
+    log_dir: Option<PathBuf>,
+
+    /// Relay data between clients instead of echoing it back (chat/relay mode).
+    #[arg(long)]
+    broadcast: bool,
+
+    /// Forward a local port to the server: `[bind_host:]bind_port:host:port`.
+    /// Repeatable.
+    #[arg(short = 'L')]
+    local_forward: Vec<String>,
+
+    /// Ask the server to forward one of its ports back to a local target:
+    /// `[bind_host:]bind_port:host:port`. Repeatable.
+    #[arg(short = 'R')]
+    remote_forward: Vec<String>,
 }
 
 #[tokio::main]
@@ -42,6 +114,10 @@ async fn main() -> Result<()> {
 
     let args = Args::parse();
 
+    if args.generate_key {
+        return run_generate_key(&args);
+    }
+
     if args.server {
         run_server(&args).await?;
     } else {
@@ -51,15 +127,50 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Generates a key pair at `--key` (and `--key.pub`) and exits, instead of
+/// running the server or client.
+fn run_generate_key(args: &Args) -> Result<()> {
+    let key_path = args
+        .key
+        .as_ref()
+        .context("--generate-key requires --key <path> to know where to write it")?;
+    let passphrase = if args.ask_passphrase {
+        keygen::prompt_passphrase()?
+    } else {
+        None
+    };
+    let pair = keygen::generate(args.key_type)?;
+    keygen::write_key_pair(&pair, key_path, passphrase.as_deref())?;
+    Ok(())
+}
+
 async fn run_server(args: &Args) -> Result<()> {
+    let host_key = match &args.key {
+        Some(path) => russh_keys::load_secret_key(path, None)?,
+        None => keygen::ephemeral_host_key()?,
+    };
     let config = Arc::new(russh::server::Config {
         inactivity_timeout: Some(Duration::from_secs(3600)),
         auth_rejection_time: Duration::from_secs(3),
-        keys: vec![russh_keys::load_secret_key(&args.key, None)?],
+        keys: vec![host_key],
         ..Default::default()
     });
 
-    let mut server = Server;
+    let authorized_keys_path = args
+        .authorized_keys
+        .clone()
+        .unwrap_or_else(auth::default_authorized_keys_path);
+    let authorized_keys = Arc::new(AuthorizedKeys::load(authorized_keys_path)?);
+    spawn_sighup_reload(authorized_keys.clone());
+
+    let mut server = Server {
+        authorized_keys,
+        record_dir: args.record.clone(),
+        log_dir: args.log_dir.clone(),
+        broadcast: args.broadcast,
+        clients: Arc::new(Mutex::new(HashMap::new())),
+        next_client_id: 0,
+    };
 
     info!("Starting server on {}:{}", args.host, args.port);
     let addr = format!("{}:{}", args.host, args.port);
@@ -67,13 +178,77 @@ async fn run_server(args: &Args) -> Result<()> {
     Ok(())
 }
 
+/// Reloads `authorized_keys` every time the process receives SIGHUP, so an
+/// operator can rotate keys without restarting the server. A no-op on
+/// platforms without Unix signals.
+fn spawn_sighup_reload(authorized_keys: Arc<AuthorizedKeys>) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        tokio::spawn(async move {
+            let mut sighup = match signal(SignalKind::hangup()) {
+                Ok(sighup) => sighup,
+                Err(err) => {
+                    log::warn!("failed to install SIGHUP handler: {}", err);
+                    return;
+                }
+            };
+            while sighup.recv().await.is_some() {
+                info!("Server: SIGHUP received, reloading authorized_keys");
+                authorized_keys.reload();
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = authorized_keys;
+    }
+}
+
 async fn run_client(args: &Args) -> Result<()> {
+    let key_path = args
+        .key
+        .as_ref()
+        .context("client requires --key (generate one first with --generate-key)")?;
     info!("Connecting to {}:{}", args.host, args.port);
-    info!("Key path: {:?}", args.key);
+    info!("Key path: {:?}", key_path);
 
-    let mut ssh = Session::connect(&args.key, &args.user, (args.host.clone(), args.port)).await?;
+    let known_hosts_path = args
+        .known_hosts
+        .clone()
+        .unwrap_or_else(known_hosts::default_known_hosts_path);
+    let known_hosts = Arc::new(KnownHosts::new(known_hosts_path, args.host_key_check));
+
+    let mut ssh = Session::connect(
+        key_path,
+        &args.user,
+        &args.host,
+        args.port,
+        known_hosts,
+    )
+    .await?;
     info!("Connected");
 
+    let mut forwarding = false;
+    for spec in &args.local_forward {
+        let (bind, target) = forwarding::parse_forward_spec(spec)?;
+        ssh.start_local_forward(bind, target).await?;
+        forwarding = true;
+    }
+    for spec in &args.remote_forward {
+        let (bind, target) = forwarding::parse_forward_spec(spec)?;
+        ssh.start_remote_forward(bind, target).await?;
+        forwarding = true;
+    }
+
+    if forwarding {
+        info!("Client: forwarding active, press Ctrl-C to exit");
+        tokio::signal::ctrl_c().await?;
+        ssh.close().await?;
+        return Ok(());
+    }
+
     let data = b"foo";
     let code = ssh.send(data).await?;
 
@@ -82,17 +257,325 @@ async fn run_client(args: &Args) -> Result<()> {
     Ok(())
 }
 
-struct Server;
+/// All live clients' channel handles, keyed by `(client_id, channel)`, used to
+/// fan data out to everyone else when `--broadcast` is enabled.
+type ClientMap = Arc<Mutex<HashMap<(usize, ChannelId), Handle>>>;
+
+struct Server {
+    authorized_keys: Arc<AuthorizedKeys>,
+    record_dir: Option<PathBuf>,
+    log_dir: Option<PathBuf>,
+    broadcast: bool,
+    clients: ClientMap,
+    next_client_id: usize,
+}
 
 impl server::Server for Server {
     type Handler = ServerHandler;
 
-    fn new_client(&mut self, _peer_addr: Option<std::net::SocketAddr>) -> Self::Handler {
-        ServerHandler
+    fn new_client(&mut self, peer_addr: Option<std::net::SocketAddr>) -> Self::Handler {
+        let client_id = self.next_client_id;
+        self.next_client_id += 1;
+        let audit = self
+            .log_dir
+            .as_ref()
+            .map(|dir| AuditLog::new(dir, peer_addr));
+        ServerHandler::new(
+            self.authorized_keys.clone(),
+            self.record_dir.clone(),
+            audit,
+            peer_addr,
+            client_id,
+            self.clients.clone(),
+            self.broadcast,
+        )
     }
 }
 
-struct ServerHandler;
+/// Terminal geometry, mode bits, and the allocated pty requested via
+/// `pty_request`, kept around so a later `shell_request`/`exec_request` can
+/// attach the child process to the pty and so `window_change_request` can
+/// resize it in place. `session` is `None` if pty allocation itself failed,
+/// in which case the channel falls back to plain pipes.
+struct PtyRequest {
+    term: String,
+    col_width: u32,
+    row_height: u32,
+    pix_width: u32,
+    pix_height: u32,
+    session: Option<pty::PtySession>,
+}
+
+/// Where inbound channel data is written once a shell/command is running on
+/// that channel.
+enum ChannelInput {
+    Pipe(ChildStdin),
+    Pty(tokio::fs::File),
+}
+
+impl ChannelInput {
+    async fn write_all(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            ChannelInput::Pipe(stdin) => stdin.write_all(data).await,
+            ChannelInput::Pty(master) => master.write_all(data).await,
+        }
+    }
+}
+
+struct ServerHandler {
+    authorized_keys: Arc<AuthorizedKeys>,
+    record_dir: Option<PathBuf>,
+    audit: Option<AuditLog>,
+    peer_addr: Option<std::net::SocketAddr>,
+    ptys: HashMap<ChannelId, PtyRequest>,
+    /// Where to write data received from the client for each channel with a
+    /// running shell/command: the child's stdin directly, or the pty master
+    /// if one was allocated for it.
+    stdins: HashMap<ChannelId, ChannelInput>,
+    recorders: HashMap<ChannelId, Arc<Recorder>>,
+    /// Unique id assigned to this client by `Server::new_client`, used as part
+    /// of this client's keys in `clients`.
+    client_id: usize,
+    clients: ClientMap,
+    broadcast: bool,
+    /// Write half of the TCP stream backing each `direct-tcpip`/
+    /// `forwarded-tcpip` channel. Shared (rather than a plain field) because
+    /// a `forwarded-tcpip` channel is registered from the detached
+    /// `tcpip_forward` listener task, outside of any `&mut self` callback.
+    tcp_writers: Arc<Mutex<HashMap<ChannelId, OwnedWriteHalf>>>,
+}
+
+impl ServerHandler {
+    fn new(
+        authorized_keys: Arc<AuthorizedKeys>,
+        record_dir: Option<PathBuf>,
+        audit: Option<AuditLog>,
+        peer_addr: Option<std::net::SocketAddr>,
+        client_id: usize,
+        clients: ClientMap,
+        broadcast: bool,
+    ) -> Self {
+        Self {
+            authorized_keys,
+            record_dir,
+            audit,
+            peer_addr,
+            ptys: HashMap::new(),
+            stdins: HashMap::new(),
+            recorders: HashMap::new(),
+            client_id,
+            clients,
+            broadcast,
+            tcp_writers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Starts recording `channel` to a new cast file under `record_dir`, named
+    /// by the current time and peer address, if recording is enabled.
+    fn start_recording(&mut self, channel: ChannelId) {
+        let Some(dir) = &self.record_dir else {
+            return;
+        };
+
+        let peer = self
+            .peer_addr
+            .map(|addr| addr.to_string().replace([':', '.'], "-"))
+            .unwrap_or_else(|| "unknown".to_string());
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = dir.join(format!("{}-{}.cast", timestamp, peer));
+
+        match Recorder::create(&path, 80, 24) {
+            Ok(recorder) => {
+                info!("Server: recording channel {} to {:?}", channel, path);
+                self.recorders.insert(channel, Arc::new(recorder));
+            }
+            Err(err) => {
+                log::warn!("Server: failed to start recording channel {}: {:#}", channel, err)
+            }
+        }
+    }
+
+    /// Spawns `command` attached to `channel`: to the pty allocated by an
+    /// earlier `pty_request` if there is one, otherwise to plain pipes.
+    /// Registers background tasks that forward output back to the client and
+    /// report the exit status once the process terminates.
+    fn spawn_process(
+        &mut self,
+        channel: ChannelId,
+        mut command: Command,
+        session: &mut ServerSession,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(pty) = self.ptys.get(&channel) {
+            command.env("TERM", &pty.term);
+        }
+
+        // Built while `self.ptys` is still borrowed; `self.stdins` isn't
+        // touched until after this match, once the borrow has ended.
+        let pty_io = match self.ptys.get(&channel).and_then(|pty| pty.session.as_ref()) {
+            Some(session_pty) => Some((
+                session_pty.child_stdio()?,
+                session_pty.slave_raw_fd(),
+                session_pty.try_clone_master()?,
+                session_pty.try_clone_master()?,
+            )),
+            None => None,
+        };
+
+        let recorder = self.recorders.get(&channel).cloned();
+
+        if let Some(((stdin_fd, stdout_fd, stderr_fd), slave_fd, reader, writer)) = pty_io {
+            command
+                .stdin(stdin_fd)
+                .stdout(stdout_fd)
+                .stderr(stderr_fd);
+            // Safety: runs in the forked child, after fork and before exec,
+            // as required by `pre_exec`.
+            unsafe {
+                command.pre_exec(move || pty::make_controlling_terminal(slave_fd));
+            }
+
+            let mut child = command.spawn()?;
+            let mut reader = reader;
+
+            let handle = session.handle();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                loop {
+                    match reader.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => {
+                            if let Some(recorder) = &recorder {
+                                recorder.record(RecordStream::Output, &buf[..n]);
+                            }
+                            if handle.data(channel, CryptoVec::from(&buf[..n])).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            });
+
+            let handle = session.handle();
+            tokio::spawn(async move {
+                if let Ok(status) = child.wait().await {
+                    let code = status.code().unwrap_or(1) as u32;
+                    let _ = handle.exit_status_request(channel, code).await;
+                }
+                let _ = handle.close(channel).await;
+            });
+
+            self.stdins.insert(channel, ChannelInput::Pty(writer));
+            return Ok(());
+        }
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let stdin = child.stdin.take().expect("child spawned with piped stdin");
+        let mut stdout = child.stdout.take().expect("child spawned with piped stdout");
+        let mut stderr = child.stderr.take().expect("child spawned with piped stderr");
+        let stderr_recorder = recorder.clone();
+
+        let handle = session.handle();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            loop {
+                match stdout.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if let Some(recorder) = &recorder {
+                            recorder.record(RecordStream::Output, &buf[..n]);
+                        }
+                        if handle.data(channel, CryptoVec::from(&buf[..n])).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        let handle = session.handle();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            loop {
+                match stderr.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if let Some(recorder) = &stderr_recorder {
+                            recorder.record(RecordStream::Output, &buf[..n]);
+                        }
+                        if handle
+                            .extended_data(channel, 1, CryptoVec::from(&buf[..n]))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        let handle = session.handle();
+        tokio::spawn(async move {
+            if let Ok(status) = child.wait().await {
+                let code = status.code().unwrap_or(1) as u32;
+                let _ = handle.exit_status_request(channel, code).await;
+            }
+            let _ = handle.close(channel).await;
+        });
+
+        self.stdins.insert(channel, ChannelInput::Pipe(stdin));
+        Ok(())
+    }
+}
+
+/// Registers `stream`'s write half under `channel_id` in `tcp_writers` (so
+/// inbound channel data, dispatched via `ServerHandler::data`, can be
+/// forwarded to it) and spawns a task pumping the read half back to the
+/// client via `handle`. Shared by `channel_open_direct_tcpip` and the
+/// `tcpip_forward` listener.
+fn spawn_tcp_forward(
+    channel_id: ChannelId,
+    stream: TcpStream,
+    handle: Handle,
+    tcp_writers: Arc<Mutex<HashMap<ChannelId, OwnedWriteHalf>>>,
+) {
+    let (mut read_half, write_half) = stream.into_split();
+    tcp_writers.lock().unwrap().insert(channel_id, write_half);
+
+    tokio::spawn(async move {
+        let mut buf = [0u8; 4096];
+        loop {
+            match read_half.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if handle.data(channel_id, CryptoVec::from(&buf[..n])).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        tcp_writers.lock().unwrap().remove(&channel_id);
+        let _ = handle.close(channel_id).await;
+    });
+}
+
+impl Drop for ServerHandler {
+    fn drop(&mut self) {
+        if self.broadcast {
+            if let Ok(mut clients) = self.clients.lock() {
+                clients.retain(|&(id, _), _| id != self.client_id);
+            }
+        }
+    }
+}
 
 #[async_trait]
 impl server::Handler for ServerHandler {
@@ -103,9 +586,22 @@ impl server::Handler for ServerHandler {
         user: &str,
         public_key: &key::PublicKey,
     ) -> Result<Auth, Self::Error> {
-        info!("Server: Received auth request for user: {}", user);
-        info!("Server: Received public key: {:?}", public_key);
-        Ok(Auth::Accept)
+        let fingerprint = public_key.fingerprint();
+        if self.authorized_keys.is_authorized(public_key) {
+            info!("Server: accepted key {} for user {}", fingerprint, user);
+            if let Some(audit) = &self.audit {
+                audit.record(&format!("auth accepted user={} key={}", user, fingerprint));
+            }
+            Ok(Auth::Accept)
+        } else {
+            info!("Server: rejected key {} for user {}", fingerprint, user);
+            if let Some(audit) = &self.audit {
+                audit.record(&format!("auth rejected user={} key={}", user, fingerprint));
+            }
+            Ok(Auth::Reject {
+                proceed_with_methods: None,
+            })
+        }
     }
 
     async fn channel_open_session(
@@ -114,6 +610,13 @@ impl server::Handler for ServerHandler {
         session: &mut ServerSession,
     ) -> Result<bool, Self::Error> {
         session.channel_success(channel.id());
+        self.start_recording(channel.id());
+        if self.broadcast {
+            self.clients
+                .lock()
+                .unwrap()
+                .insert((self.client_id, channel.id()), session.handle());
+        }
         Ok(true)
     }
 
@@ -123,22 +626,259 @@ impl server::Handler for ServerHandler {
         _session: &mut ServerSession,
     ) -> Result<(), Self::Error> {
         info!("Server: Channel {} closed by client", channel);
+        self.stdins.remove(&channel);
+        self.ptys.remove(&channel);
+        self.recorders.remove(&channel);
+        self.tcp_writers.lock().unwrap().remove(&channel);
+        self.clients.lock().unwrap().remove(&(self.client_id, channel));
+
+        Ok(())
+    }
+
+    async fn pty_request(
+        &mut self,
+        channel: ChannelId,
+        term: &str,
+        col_width: u32,
+        row_height: u32,
+        pix_width: u32,
+        pix_height: u32,
+        modes: &[(Pty, u32)],
+        session: &mut ServerSession,
+    ) -> Result<(), Self::Error> {
+        info!(
+            "Server: pty_request on channel {}: term={} {}x{}",
+            channel, term, col_width, row_height
+        );
+        let session_pty = match pty::PtySession::open(col_width, row_height) {
+            Ok(session_pty) => Some(session_pty),
+            Err(err) => {
+                log::warn!(
+                    "Server: failed to allocate a pty for channel {}, falling back to pipes: {:#}",
+                    channel, err
+                );
+                None
+            }
+        };
+        if let Some(session_pty) = &session_pty {
+            if let Err(err) = session_pty.apply_modes(modes) {
+                log::warn!(
+                    "Server: failed to apply terminal modes on channel {}: {:#}",
+                    channel, err
+                );
+            }
+        }
+        self.ptys.insert(
+            channel,
+            PtyRequest {
+                term: term.to_string(),
+                col_width,
+                row_height,
+                pix_width,
+                pix_height,
+                session: session_pty,
+            },
+        );
+        if let Some(recorder) = self.recorders.get(&channel) {
+            recorder.set_size(col_width, row_height);
+        }
+        session.channel_success(channel);
+        Ok(())
+    }
 
+    async fn window_change_request(
+        &mut self,
+        channel: ChannelId,
+        col_width: u32,
+        row_height: u32,
+        pix_width: u32,
+        pix_height: u32,
+        _session: &mut ServerSession,
+    ) -> Result<(), Self::Error> {
+        if let Some(pty) = self.ptys.get_mut(&channel) {
+            pty.col_width = col_width;
+            pty.row_height = row_height;
+            pty.pix_width = pix_width;
+            pty.pix_height = pix_height;
+            if let Some(session_pty) = &pty.session {
+                if let Err(err) = session_pty.resize(col_width, row_height) {
+                    log::warn!(
+                        "Server: failed to resize pty on channel {}: {:#}",
+                        channel, err
+                    );
+                }
+            }
+            info!(
+                "Server: window_change on channel {}: {}x{}",
+                channel, col_width, row_height
+            );
+        }
         Ok(())
     }
 
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut ServerSession,
+    ) -> Result<(), Self::Error> {
+        let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+        info!("Server: shell_request on channel {}: {}", channel, shell);
+        self.spawn_process(channel, Command::new(shell), session)?;
+        session.channel_success(channel);
+        Ok(())
+    }
+
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut ServerSession,
+    ) -> Result<(), Self::Error> {
+        let command = String::from_utf8_lossy(data).into_owned();
+        info!("Server: exec_request on channel {}: {}", channel, command);
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(&command);
+        self.spawn_process(channel, cmd, session)?;
+        session.channel_success(channel);
+        Ok(())
+    }
+
+    async fn channel_open_direct_tcpip(
+        &mut self,
+        channel: Channel<server::Msg>,
+        host_to_connect: &str,
+        port_to_connect: u32,
+        originator_address: &str,
+        originator_port: u32,
+        session: &mut ServerSession,
+    ) -> Result<bool, Self::Error> {
+        info!(
+            "Server: direct-tcpip from {}:{} to {}:{}",
+            originator_address, originator_port, host_to_connect, port_to_connect
+        );
+        let stream = TcpStream::connect((host_to_connect, port_to_connect as u16)).await?;
+        spawn_tcp_forward(channel.id(), stream, session.handle(), self.tcp_writers.clone());
+        Ok(true)
+    }
+
+    /// Handles the `tcpip_forward` global request behind `-R`: binds the
+    /// requested address/port and opens a `forwarded-tcpip` channel back to
+    /// the client for every connection it accepts.
+    async fn tcpip_forward(
+        &mut self,
+        address: &str,
+        port: &mut u32,
+        session: &mut ServerSession,
+    ) -> Result<bool, Self::Error> {
+        let listener = TcpListener::bind((address, *port as u16)).await?;
+        if *port == 0 {
+            *port = listener.local_addr()?.port() as u32;
+        }
+        info!("Server: forwarding {}:{} to connecting clients", address, port);
+
+        let handle = session.handle();
+        let tcp_writers = self.tcp_writers.clone();
+        let bind_address = address.to_string();
+        let bind_port = *port;
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(pair) => pair,
+                    Err(err) => {
+                        log::warn!("Server: tcpip_forward accept failed: {}", err);
+                        break;
+                    }
+                };
+
+                let handle = handle.clone();
+                let tcp_writers = tcp_writers.clone();
+                let bind_address = bind_address.clone();
+                tokio::spawn(async move {
+                    let channel = match handle
+                        .channel_open_forwarded_tcpip(
+                            bind_address,
+                            bind_port,
+                            peer.ip().to_string(),
+                            peer.port() as u32,
+                        )
+                        .await
+                    {
+                        Ok(channel) => channel,
+                        Err(err) => {
+                            log::warn!("Server: failed to open forwarded-tcpip channel: {}", err);
+                            return;
+                        }
+                    };
+                    let channel_id = channel.id();
+                    let inner_handle = handle.clone();
+                    spawn_tcp_forward(channel_id, stream, inner_handle, tcp_writers);
+                });
+            }
+        });
+
+        Ok(true)
+    }
+
     async fn data(
         &mut self,
         channel: ChannelId,
         data: &[u8],
         session: &mut ServerSession,
     ) -> Result<(), Self::Error> {
+        if let Some(recorder) = self.recorders.get(&channel) {
+            recorder.record(RecordStream::Input, data);
+        }
+        if let Some(audit) = &self.audit {
+            audit.record(&format!(
+                "channel {} data: {}",
+                channel,
+                String::from_utf8_lossy(data)
+            ));
+        }
+
+        if self.broadcast {
+            let targets: Vec<(ChannelId, Handle)> = self
+                .clients
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|((id, _), _)| *id != self.client_id)
+                .map(|(&(_, ch), handle)| (ch, handle.clone()))
+                .collect();
+
+            for (ch, handle) in targets {
+                let _ = handle.data(ch, CryptoVec::from(data)).await;
+            }
+            return Ok(());
+        }
+
+        if let Some(stdin) = self.stdins.get_mut(&channel) {
+            stdin.write_all(data).await?;
+            return Ok(());
+        }
+
+        // Forwarded TCP data. Removed-then-reinserted rather than held
+        // across the `.await` so the write doesn't keep the std mutex
+        // locked while yielding.
+        if let Some(mut writer) = self.tcp_writers.lock().unwrap().remove(&channel) {
+            let result = writer.write_all(data).await;
+            if result.is_ok() {
+                self.tcp_writers.lock().unwrap().insert(channel, writer);
+            }
+            result?;
+            return Ok(());
+        }
+
         let received_str = std::str::from_utf8(data)?;
         info!("Server: Received data from client: {}", received_str);
 
         let response = format!("Server processed: {}", received_str);
         info!("Server: Sending response to client: {}", response);
 
+        if let Some(recorder) = self.recorders.get(&channel) {
+            recorder.record(RecordStream::Output, response.as_bytes());
+        }
+
         session.data(
             channel,
             russh::CryptoVec::from(response.as_bytes().to_vec()),
@@ -148,9 +888,18 @@ impl server::Handler for ServerHandler {
     }
 }
 
+/// Bind endpoint -> local target endpoint for each active `-R` remote
+/// forward, so an incoming `forwarded-tcpip` channel can be matched back to
+/// where it should connect locally.
+type RemoteForwardMap = Arc<Mutex<HashMap<(String, u16), (String, u16)>>>;
+
 #[allow(dead_code)]
 struct ClientHandler {
     user: String,
+    host: String,
+    port: u16,
+    known_hosts: Arc<KnownHosts>,
+    remote_forwards: RemoteForwardMap,
 }
 
 #[async_trait]
@@ -159,21 +908,76 @@ impl client::Handler for ClientHandler {
 
     async fn check_server_key(
         &mut self,
-        _server_public_key: &key::PublicKey,
+        server_public_key: &key::PublicKey,
     ) -> Result<bool, Self::Error> {
-        Ok(true)
+        match self.known_hosts.check(&self.host, self.port, server_public_key) {
+            Ok(trusted) => Ok(trusted),
+            Err(err) => {
+                log::error!("known_hosts: failed to verify host key: {:#}", err);
+                Ok(false)
+            }
+        }
+    }
+
+    async fn channel_open_forwarded_tcpip(
+        &mut self,
+        channel: Channel<client::Msg>,
+        connected_address: &str,
+        connected_port: u32,
+        originator_address: &str,
+        originator_port: u32,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        let target = self
+            .remote_forwards
+            .lock()
+            .unwrap()
+            .get(&(connected_address.to_string(), connected_port as u16))
+            .cloned();
+
+        let Some((target_host, target_port)) = target else {
+            log::warn!(
+                "Client: forwarded-tcpip for unregistered bind {}:{}",
+                connected_address,
+                connected_port
+            );
+            return Ok(());
+        };
+
+        info!(
+            "Client: forwarded connection from {}:{} -> {}:{}",
+            originator_address, originator_port, target_host, target_port
+        );
+
+        tokio::spawn(async move {
+            let stream = match TcpStream::connect((target_host.as_str(), target_port)).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log::warn!("Client: failed to connect to forward target: {}", err);
+                    return;
+                }
+            };
+            if let Err(err) = forwarding::bridge_client_channel(stream, channel).await {
+                log::warn!("Client: remote forward connection ended: {:#}", err);
+            }
+        });
+
+        Ok(())
     }
 }
 
 pub struct Session {
     session: client::Handle<ClientHandler>,
+    remote_forwards: RemoteForwardMap,
 }
 
 impl Session {
-    async fn connect<P: AsRef<Path>, A: ToSocketAddrs>(
+    async fn connect<P: AsRef<Path>>(
         key_path: P,
         user: impl Into<String>,
-        addrs: A,
+        host: impl Into<String>,
+        port: u16,
+        known_hosts: Arc<KnownHosts>,
     ) -> Result<Self> {
         let key_pair = load_secret_key(key_path, None)?;
         let config = client::Config {
@@ -183,11 +987,17 @@ impl Session {
 
         let config = Arc::new(config);
         let user_string = user.into();
+        let host_string = host.into();
+        let remote_forwards: RemoteForwardMap = Arc::new(Mutex::new(HashMap::new()));
         let sh = ClientHandler {
             user: user_string.clone(),
+            host: host_string.clone(),
+            port,
+            known_hosts,
+            remote_forwards: remote_forwards.clone(),
         };
 
-        let mut session = client::connect(config, addrs, sh).await?;
+        let mut session = client::connect(config, (host_string, port), sh).await?;
         let auth_res = session
             .authenticate_publickey(user_string, Arc::new(key_pair))
             .await?;
@@ -196,7 +1006,78 @@ impl Session {
             anyhow::bail!("Authentication failed");
         }
 
-        Ok(Self { session })
+        Ok(Self {
+            session,
+            remote_forwards,
+        })
+    }
+
+    /// Listens on `bind` and opens a `direct-tcpip` channel to `target` for
+    /// every connection accepted, bridging the two (`-L`).
+    async fn start_local_forward(&self, bind: Endpoint, target: Endpoint) -> Result<()> {
+        let listener = TcpListener::bind((bind.host.as_str(), bind.port)).await?;
+        info!(
+            "Client: listening on {}:{}, forwarding to {}:{}",
+            bind.host, bind.port, target.host, target.port
+        );
+
+        let handle = self.session.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(pair) => pair,
+                    Err(err) => {
+                        log::warn!("Client: local forward accept failed: {}", err);
+                        continue;
+                    }
+                };
+
+                let handle = handle.clone();
+                let target = target.clone();
+                tokio::spawn(async move {
+                    let channel = match handle
+                        .channel_open_direct_tcpip(
+                            target.host.clone(),
+                            target.port as u32,
+                            peer.ip().to_string(),
+                            peer.port() as u32,
+                        )
+                        .await
+                    {
+                        Ok(channel) => channel,
+                        Err(err) => {
+                            log::warn!("Client: failed to open direct-tcpip channel: {}", err);
+                            return;
+                        }
+                    };
+                    if let Err(err) = forwarding::bridge_client_channel(stream, channel).await {
+                        log::warn!("Client: local forward connection ended: {:#}", err);
+                    }
+                });
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Asks the server to listen on `bind` and registers `target` as where
+    /// to connect locally when it hands us a `forwarded-tcpip` channel
+    /// (`-R`). Registers under the port the server actually bound, not the
+    /// one requested, since `bind.port == 0` asks the server to pick one.
+    async fn start_remote_forward(&self, bind: Endpoint, target: Endpoint) -> Result<()> {
+        let bound_port = self
+            .session
+            .tcpip_forward(bind.host.clone(), bind.port as u32)
+            .await? as u16;
+        self.remote_forwards.lock().unwrap().insert(
+            (bind.host.clone(), bound_port),
+            (target.host.clone(), target.port),
+        );
+        info!(
+            "Client: asked server to forward {}:{} to {}:{}",
+            bind.host, bound_port, target.host, target.port
+        );
+        Ok(())
     }
 
     async fn send(&mut self, input_data: &[u8]) -> Result<Vec<u8>> {
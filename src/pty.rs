@@ -0,0 +1,168 @@
+//! Pseudo-terminal allocation for channels with an active `pty_request`,
+//! so the spawned shell/command gets a real controlling terminal instead
+//! of plain pipes.
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::process::Stdio;
+
+use anyhow::{Context, Result};
+use nix::pty::{openpty, Winsize};
+use nix::sys::termios::{
+    self, ControlFlags, InputFlags, LocalFlags, OutputFlags, SetArg, SpecialCharacterIndices,
+};
+use russh::Pty;
+
+nix::ioctl_write_ptr_bad!(set_window_size, nix::libc::TIOCSWINSZ, Winsize);
+nix::ioctl_none_bad!(set_controlling_tty, nix::libc::TIOCSCTTY);
+
+/// An allocated pty pair for one channel.
+pub struct PtySession {
+    master: std::fs::File,
+    slave: OwnedFd,
+}
+
+impl PtySession {
+    /// Opens a new pty pair sized to `cols`x`rows`.
+    pub fn open(cols: u32, rows: u32) -> Result<Self> {
+        let pair = openpty(Some(&to_winsize(cols, rows)), None).context("allocating a pty")?;
+        Ok(Self {
+            master: std::fs::File::from(pair.master),
+            slave: pair.slave,
+        })
+    }
+
+    /// Fresh duplicates of the master fd, one for the background reader
+    /// task and one to hold in `ServerHandler::stdins` for writes.
+    pub fn try_clone_master(&self) -> Result<tokio::fs::File> {
+        let dup = self.master.try_clone().context("duplicating pty master fd")?;
+        Ok(tokio::fs::File::from_std(dup))
+    }
+
+    /// Stdio handles for the slave side, one per standard stream. A real
+    /// terminal has no separate stdout/stderr, so all three are duplicates
+    /// of the same fd.
+    pub fn child_stdio(&self) -> Result<(Stdio, Stdio, Stdio)> {
+        Ok((
+            Stdio::from(dup(&self.slave)?),
+            Stdio::from(dup(&self.slave)?),
+            Stdio::from(dup(&self.slave)?),
+        ))
+    }
+
+    /// Raw fd of the slave half, for use in a `pre_exec` closure (which must
+    /// not borrow `self`, since it runs after `fork` in the child).
+    pub fn slave_raw_fd(&self) -> RawFd {
+        self.slave.as_raw_fd()
+    }
+
+    /// Updates the pty's window size via `TIOCSWINSZ`.
+    pub fn resize(&self, cols: u32, rows: u32) -> Result<()> {
+        let winsize = to_winsize(cols, rows);
+        unsafe { set_window_size(self.master.as_raw_fd(), &winsize) }
+            .context("setting pty window size")?;
+        Ok(())
+    }
+
+    /// Applies the `pty_request` mode list (RFC 4254 §8) to this pty's
+    /// termios. Master and slave share one termios, so either fd works here.
+    pub fn apply_modes(&self, modes: &[(Pty, u32)]) -> Result<()> {
+        let mut attrs = termios::tcgetattr(self.master.as_raw_fd()).context("reading pty termios")?;
+        for &(opcode, value) in modes {
+            apply_mode(&mut attrs, opcode, value);
+        }
+        termios::tcsetattr(self.master.as_raw_fd(), SetArg::TCSANOW, &attrs)
+            .context("applying pty termios")?;
+        Ok(())
+    }
+}
+
+/// Sets the one termios flag or special character `opcode` describes to
+/// `value`. Opcodes this doesn't recognize (baud rate and a handful of
+/// rare/BSD-only ones like VSTATUS or XCASE) are silently skipped rather than
+/// failing the whole pty_request over one unsupported mode.
+fn apply_mode(attrs: &mut termios::Termios, opcode: Pty, value: u32) {
+    let on = value != 0;
+    match opcode {
+        Pty::ISIG => attrs.local_flags.set(LocalFlags::ISIG, on),
+        Pty::ICANON => attrs.local_flags.set(LocalFlags::ICANON, on),
+        Pty::ECHO => attrs.local_flags.set(LocalFlags::ECHO, on),
+        Pty::ECHOE => attrs.local_flags.set(LocalFlags::ECHOE, on),
+        Pty::ECHOK => attrs.local_flags.set(LocalFlags::ECHOK, on),
+        Pty::ECHONL => attrs.local_flags.set(LocalFlags::ECHONL, on),
+        Pty::NOFLSH => attrs.local_flags.set(LocalFlags::NOFLSH, on),
+        Pty::TOSTOP => attrs.local_flags.set(LocalFlags::TOSTOP, on),
+        Pty::IEXTEN => attrs.local_flags.set(LocalFlags::IEXTEN, on),
+        Pty::ECHOCTL => attrs.local_flags.set(LocalFlags::ECHOCTL, on),
+        Pty::ECHOKE => attrs.local_flags.set(LocalFlags::ECHOKE, on),
+        Pty::PENDIN => attrs.local_flags.set(LocalFlags::PENDIN, on),
+
+        Pty::IGNPAR => attrs.input_flags.set(InputFlags::IGNPAR, on),
+        Pty::PARMRK => attrs.input_flags.set(InputFlags::PARMRK, on),
+        Pty::INPCK => attrs.input_flags.set(InputFlags::INPCK, on),
+        Pty::ISTRIP => attrs.input_flags.set(InputFlags::ISTRIP, on),
+        Pty::INLCR => attrs.input_flags.set(InputFlags::INLCR, on),
+        Pty::IGNCR => attrs.input_flags.set(InputFlags::IGNCR, on),
+        Pty::ICRNL => attrs.input_flags.set(InputFlags::ICRNL, on),
+        Pty::IXON => attrs.input_flags.set(InputFlags::IXON, on),
+        Pty::IXANY => attrs.input_flags.set(InputFlags::IXANY, on),
+        Pty::IXOFF => attrs.input_flags.set(InputFlags::IXOFF, on),
+        Pty::IMAXBEL => attrs.input_flags.set(InputFlags::IMAXBEL, on),
+        Pty::IUTF8 => attrs.input_flags.set(InputFlags::IUTF8, on),
+
+        Pty::OPOST => attrs.output_flags.set(OutputFlags::OPOST, on),
+        Pty::ONLCR => attrs.output_flags.set(OutputFlags::ONLCR, on),
+        Pty::OCRNL => attrs.output_flags.set(OutputFlags::OCRNL, on),
+        Pty::ONOCR => attrs.output_flags.set(OutputFlags::ONOCR, on),
+        Pty::ONLRET => attrs.output_flags.set(OutputFlags::ONLRET, on),
+
+        Pty::CS7 => attrs.control_flags.set(ControlFlags::CS7, on),
+        Pty::CS8 => attrs.control_flags.set(ControlFlags::CS8, on),
+        Pty::PARENB => attrs.control_flags.set(ControlFlags::PARENB, on),
+        Pty::PARODD => attrs.control_flags.set(ControlFlags::PARODD, on),
+
+        Pty::VINTR => attrs.control_chars[SpecialCharacterIndices::VINTR as usize] = value as u8,
+        Pty::VQUIT => attrs.control_chars[SpecialCharacterIndices::VQUIT as usize] = value as u8,
+        Pty::VERASE => attrs.control_chars[SpecialCharacterIndices::VERASE as usize] = value as u8,
+        Pty::VKILL => attrs.control_chars[SpecialCharacterIndices::VKILL as usize] = value as u8,
+        Pty::VEOF => attrs.control_chars[SpecialCharacterIndices::VEOF as usize] = value as u8,
+        Pty::VEOL => attrs.control_chars[SpecialCharacterIndices::VEOL as usize] = value as u8,
+        Pty::VEOL2 => attrs.control_chars[SpecialCharacterIndices::VEOL2 as usize] = value as u8,
+        Pty::VSTART => attrs.control_chars[SpecialCharacterIndices::VSTART as usize] = value as u8,
+        Pty::VSTOP => attrs.control_chars[SpecialCharacterIndices::VSTOP as usize] = value as u8,
+        Pty::VSUSP => attrs.control_chars[SpecialCharacterIndices::VSUSP as usize] = value as u8,
+        Pty::VREPRINT => attrs.control_chars[SpecialCharacterIndices::VREPRINT as usize] = value as u8,
+        Pty::VWERASE => attrs.control_chars[SpecialCharacterIndices::VWERASE as usize] = value as u8,
+        Pty::VLNEXT => attrs.control_chars[SpecialCharacterIndices::VLNEXT as usize] = value as u8,
+        Pty::VDISCARD => attrs.control_chars[SpecialCharacterIndices::VDISCARD as usize] = value as u8,
+
+        _ => {}
+    }
+}
+
+/// Starts a new session and makes `slave_fd` its controlling terminal. Must
+/// run in the child after `fork` and before `exec` (see
+/// `Command::pre_exec`), which is why this takes a raw fd rather than
+/// borrowing a `PtySession`.
+///
+/// # Safety
+/// Only safe to call from a `pre_exec` closure, i.e. in the forked child
+/// before it execs, per the same contract as `pre_exec` itself.
+pub unsafe fn make_controlling_terminal(slave_fd: RawFd) -> std::io::Result<()> {
+    nix::unistd::setsid().map_err(std::io::Error::from)?;
+    set_controlling_tty(slave_fd).map_err(std::io::Error::from)?;
+    Ok(())
+}
+
+fn dup(fd: &OwnedFd) -> Result<OwnedFd> {
+    let raw = nix::unistd::dup(fd.as_raw_fd()).context("duplicating pty fd")?;
+    Ok(unsafe { OwnedFd::from_raw_fd(raw) })
+}
+
+fn to_winsize(cols: u32, rows: u32) -> Winsize {
+    Winsize {
+        ws_row: rows as u16,
+        ws_col: cols as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
@@ -0,0 +1,118 @@
+//! Per-channel session recording in the asciinema v2 "cast" format.
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::Serialize;
+
+/// Which direction a recorded chunk of data travelled.
+#[derive(Clone, Copy)]
+pub enum Stream {
+    Output,
+    Input,
+}
+
+impl Stream {
+    fn code(self) -> &'static str {
+        match self {
+            Stream::Output => "o",
+            Stream::Input => "i",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CastHeader {
+    version: u8,
+    width: u32,
+    height: u32,
+    timestamp: u64,
+}
+
+/// Records every byte flowing through a channel, along with its offset from
+/// the start of the recording, and appends it to an asciinema v2 cast file
+/// as it arrives.
+///
+/// The header line carries the terminal size, but recording starts (at
+/// `channel_open_session`) before a `pty_request` for the real size, if any,
+/// has arrived. So the header isn't written until the first `record` call;
+/// `set_size` can update the size any time before then, letting a later
+/// `pty_request` correct the default passed to `create`.
+pub struct Recorder {
+    file: Mutex<std::fs::File>,
+    start: Instant,
+    size: Mutex<(u32, u32)>,
+    header_written: AtomicBool,
+}
+
+impl Recorder {
+    /// Creates `path` (and any missing parent directories). The asciinema
+    /// header isn't written until the first `record` call.
+    pub fn create(path: &Path, width: u32, height: u32) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("creating {}", parent.display()))?;
+        }
+
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("creating cast file at {}", path.display()))?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+            start: Instant::now(),
+            size: Mutex::new((width, height)),
+            header_written: AtomicBool::new(false),
+        })
+    }
+
+    /// Updates the terminal size reported in the cast header. Has no effect
+    /// once the header has already been written (i.e. after the first
+    /// `record` call).
+    pub fn set_size(&self, width: u32, height: u32) {
+        *self.size.lock().expect("recorder size lock poisoned") = (width, height);
+    }
+
+    /// Appends one `[offset, stream, data]` row, writing the header first if
+    /// this is the first call. A write error here just gets logged; we keep
+    /// the session running rather than tear it down over a recording glitch.
+    pub fn record(&self, stream: Stream, data: &[u8]) {
+        let offset = self.start.elapsed().as_secs_f64();
+        let text = String::from_utf8_lossy(data);
+        let row = serde_json::json!([offset, stream.code(), text]);
+
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+        // Checked under the file lock so no concurrent row can land before
+        // the header.
+        if !self.header_written.swap(true, Ordering::SeqCst) {
+            if let Err(err) = self.write_header(&mut file) {
+                warn!("recorder: failed to write header: {}", err);
+            }
+        }
+        if let Err(err) = writeln!(file, "{}", row) {
+            warn!("recorder: failed to write event: {}", err);
+        }
+    }
+
+    fn write_header(&self, file: &mut std::fs::File) -> Result<()> {
+        let (width, height) = *self.size.lock().expect("recorder size lock poisoned");
+        let header = CastHeader {
+            version: 2,
+            width,
+            height,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        writeln!(file, "{}", serde_json::to_string(&header)?)?;
+        Ok(())
+    }
+}